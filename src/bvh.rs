@@ -0,0 +1,293 @@
+use glam::Vec3;
+
+use crate::Vertex;
+
+
+/// maximum number of triangles stored in a single leaf before the node is split further
+const MAX_LEAF_TRIANGLES : usize = 4;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self { min: Vec3::splat(f32::INFINITY), max: Vec3::splat(f32::NEG_INFINITY) }
+    }
+
+    pub fn extend(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// slab test, returns the entry distance `t` if the ray hits this box
+    fn hit(&self, origin: Vec3, dir: Vec3, inv_dir: Vec3) -> Option<f32> {
+        let mut tmin = f32::NEG_INFINITY;
+        let mut tmax = f32::INFINITY;
+
+        for axis in 0..3 {
+            if dir[axis].abs() < f32::EPSILON {
+                // ray is parallel to this slab, it only matters if the origin
+                // already lies within it
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+
+                continue;
+            }
+
+            let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+
+            tmin = tmin.max(t1.min(t2));
+            tmax = tmax.min(t1.max(t2));
+        }
+
+        if tmin > tmax || tmax < 0.0 {
+            return None;
+        }
+
+        Some(tmin.max(0.0))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Triangle {
+    positions: [Vec3; 3],
+    aabb: Aabb,
+    centroid: Vec3,
+    index: u32,
+}
+
+
+enum Node {
+    Leaf(Aabb, Vec<u32>),
+    Branch(Box<Node>, Box<Node>, Aabb),
+}
+
+impl Node {
+    fn aabb(&self) -> Aabb {
+        match self {
+            Node::Leaf(aabb, _) => *aabb,
+            Node::Branch(_, _, aabb) => *aabb,
+        }
+    }
+}
+
+
+/// the result of a successful [`Bvh::raycast`]
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    /// index of the hit triangle, i.e. `indices[triangle * 3..triangle * 3 + 3]`
+    pub triangle: u32,
+    pub t: f32,
+    pub barycentric: Vec3,
+}
+
+
+/// a bounding volume hierarchy over the triangles of a meshed [`crate::VoxelMesh`],
+/// used to answer ray-picking and collision queries without scanning every triangle
+pub struct Bvh {
+    triangles: Vec<Triangle>,
+    root: Node,
+}
+
+impl Bvh {
+    pub fn build(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let mut triangles : Vec<Triangle> = indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter_map(|(i, tri)| {
+                let positions = [
+                    vertices[tri[0] as usize].position,
+                    vertices[tri[1] as usize].position,
+                    vertices[tri[2] as usize].position,
+                ];
+
+                let mut aabb = Aabb::empty();
+                for p in positions { aabb.extend(p); }
+
+                // skip degenerate, zero-area triangles
+                let edge1 = positions[1] - positions[0];
+                let edge2 = positions[2] - positions[0];
+                if edge1.cross(edge2).length_squared() == 0.0 {
+                    return None;
+                }
+
+                Some(Triangle { positions, aabb, centroid: aabb.centroid(), index: i as u32 })
+            })
+            .collect();
+
+        let indices : Vec<u32> = (0..triangles.len() as u32).collect();
+        let root = Self::build_node(&mut triangles, indices);
+
+        Self { triangles, root }
+    }
+
+    fn build_node(triangles: &mut [Triangle], indices: Vec<u32>) -> Node {
+        let mut node_aabb = Aabb::empty();
+        for &i in &indices {
+            node_aabb = node_aabb.union(&triangles[i as usize].aabb);
+        }
+
+        if indices.len() <= MAX_LEAF_TRIANGLES {
+            return Node::Leaf(node_aabb, indices);
+        }
+
+        let mut centroid_bounds = Aabb::empty();
+        for &i in &indices {
+            centroid_bounds.extend(triangles[i as usize].centroid);
+        }
+
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 }
+                   else if extent.y >= extent.z { 1 }
+                   else { 2 };
+
+        // decoded meshes aren't guaranteed to have finite vertex positions, so fall
+        // back to a total order instead of panicking on a NaN centroid
+        let mut sorted = indices;
+        sorted.sort_by(|&a, &b| {
+            triangles[a as usize].centroid[axis]
+                .total_cmp(&triangles[b as usize].centroid[axis])
+        });
+
+        let mid = sorted.len() / 2;
+        let right = sorted.split_off(mid);
+        let left = sorted;
+
+        let left = Self::build_node(triangles, left);
+        let right = Self::build_node(triangles, right);
+        let aabb = left.aabb().union(&right.aabb());
+
+        Node::Branch(Box::new(left), Box::new(right), aabb)
+    }
+
+    /// casts a ray and returns the closest triangle it hits, if any
+    pub fn raycast(&self, origin: Vec3, dir: Vec3) -> Option<Hit> {
+        let inv_dir = Vec3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let mut closest : Option<Hit> = None;
+
+        self.raycast_node(&self.root, origin, dir, inv_dir, &mut closest);
+        closest
+    }
+
+    fn raycast_node(&self, node: &Node, origin: Vec3, dir: Vec3, inv_dir: Vec3, closest: &mut Option<Hit>) {
+        let max_t = closest.map_or(f32::INFINITY, |hit| hit.t);
+        let Some(t) = node.aabb().hit(origin, dir, inv_dir)
+        else { return };
+
+        if t > max_t { return }
+
+        match node {
+            Node::Leaf(_, tris) => {
+                for &i in tris {
+                    let tri = &self.triangles[i as usize];
+                    if let Some(hit) = Self::intersect_triangle(tri, origin, dir) {
+                        let is_closer = closest.map_or(true, |c| hit.t < c.t);
+                        if is_closer {
+                            *closest = Some(hit);
+                        }
+                    }
+                }
+            }
+
+            Node::Branch(left, right, _) => {
+                self.raycast_node(left, origin, dir, inv_dir, closest);
+                self.raycast_node(right, origin, dir, inv_dir, closest);
+            }
+        }
+    }
+
+    /// Möller–Trumbore ray/triangle intersection
+    fn intersect_triangle(tri: &Triangle, origin: Vec3, dir: Vec3) -> Option<Hit> {
+        let [a, b, c] = tri.positions;
+        let edge1 = b - a;
+        let edge2 = c - a;
+
+        let p = dir.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let t_vec = origin - a;
+        let u = t_vec.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = t_vec.cross(edge1);
+        let v = dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if t <= f32::EPSILON {
+            return None;
+        }
+
+        Some(Hit { triangle: tri.index, t, barycentric: Vec3::new(1.0 - u - v, u, v) })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad_mesh() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = vec![
+            Vertex::new(Vec3::new(-1.0, -1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, -1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, 1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(-1.0, 1.0, 0.0), 0xffffffff),
+        ];
+
+        let indices = vec![0, 1, 2, 2, 3, 0];
+        (vertices, indices)
+    }
+
+    #[test]
+    fn raycast_hits_quad_straight_on() {
+        let (vertices, indices) = quad_mesh();
+        let bvh = Bvh::build(&vertices, &indices);
+
+        let hit = bvh.raycast(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = hit.expect("ray through the quad's plane should hit");
+
+        assert!((hit.t - 5.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn raycast_misses_when_pointing_away() {
+        let (vertices, indices) = quad_mesh();
+        let bvh = Bvh::build(&vertices, &indices);
+
+        let hit = bvh.raycast(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(hit.is_none());
+    }
+
+    #[test]
+    fn raycast_misses_outside_quad_bounds() {
+        let (vertices, indices) = quad_mesh();
+        let bvh = Bvh::build(&vertices, &indices);
+
+        let hit = bvh.raycast(Vec3::new(5.0, 5.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(hit.is_none());
+    }
+}
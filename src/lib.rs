@@ -1,9 +1,28 @@
+pub mod bvh;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crc32fast::Hasher;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use glam::{IVec3, USizeVec3, Vec3, Vec4};
 use save_format::byte::{ByteReader, ByteWriter};
 
 
 pub const VOXEL_MESH_MAGIC : [u8; 10] = *b"VOXEL_MESH";
-pub const VOXEL_MESH_VERSION : [u8; 4] = [0, 0, 0, 1];
+pub const VOXEL_MESH_VERSION : [u8; 4] = [0, 0, 0, 2];
+
+/// number of bytes in the magic + version header, before the compression marker
+const VOXEL_MESH_HEADER_LEN : usize = VOXEL_MESH_MAGIC.len() + VOXEL_MESH_VERSION.len();
+
+
+/// selects how the vertex/index payload is stored in an encoded `VoxelMesh`
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub enum CompressionMode {
+    #[default]
+    None = 0,
+    Zlib = 1,
+}
 
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -38,14 +57,75 @@ pub struct VoxelMesh {
 pub enum VoxelMeshDecodeError {
     InvalidByteWriter,
     InvalidMagicValue,
-    EOI,
     InvalidVersion {
         lib_version: [u8; 4],
         file_version: [u8; 4],
+    },
+    UnsupportedCompression(u8),
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    UnexpectedEof {
+        offset: usize,
+        reading: &'static str,
+    },
+    CorruptLength {
+        field: &'static str,
+        declared: u32,
+        remaining: usize,
+    },
+    IndexOutOfBounds {
+        index: u32,
+        vertex_count: u32,
+    },
+}
+
+
+/// wraps a [`ByteReader`] with a byte offset so decode errors can point at the
+/// exact field and position that ran out of data
+struct Cursor<'a> {
+    reader: ByteReader<'a>,
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        Some(Self { reader: ByteReader::new(data)?, offset: 0 })
+    }
+
+    fn array<const N: usize>(&mut self, reading: &'static str) -> Result<[u8; N], VoxelMeshDecodeError> {
+        let value = self.reader.read().ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: self.offset, reading })?;
+        self.offset += N;
+        Ok(value)
+    }
+
+    fn u32(&mut self, reading: &'static str) -> Result<u32, VoxelMeshDecodeError> {
+        let value = self.reader.read_u32().ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: self.offset, reading })?;
+        self.offset += 4;
+        Ok(value)
+    }
+
+    fn f32(&mut self, reading: &'static str) -> Result<f32, VoxelMeshDecodeError> {
+        let value = self.reader.read_f32().ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: self.offset, reading })?;
+        self.offset += 4;
+        Ok(value)
     }
 }
 
 
+/// serialized size of one [`Vertex`]: 3 `f32`s of position plus a `u32` rgba
+const VERTEX_BYTE_LEN : usize = 3 * 4 + 4;
+const INDEX_BYTE_LEN : usize = 4;
+
+/// deflate can't plausibly expand a compressed payload by more than this factor;
+/// bounds the compression header's `uncompressed_len` field before it's used as
+/// an allocation size. this guards the Zlib envelope itself, as opposed to the
+/// `vertices_len`/`indices_len` budget check below, which guards the payload
+/// those bytes decompress into
+const MAX_ZLIB_EXPANSION_RATIO : usize = 1032;
+
+
 pub fn draw_quad(verticies: &mut Vec<Vertex>, indicies: &mut Vec<u32>, quad: Quad) {
     let k = verticies.len() as u32;
     for corner in quad.corners {
@@ -60,77 +140,208 @@ pub fn draw_quad(verticies: &mut Vec<Vertex>, indicies: &mut Vec<u32>, quad: Qua
 
 
 impl VoxelMesh {
+    /// encodes with [`CompressionMode::None`], kept around so existing callers
+    /// don't have to pick a compression mode
     pub fn encode(&self) -> Vec<u8> {
-        let mut writer = ByteWriter::new();
-        writer.write(VOXEL_MESH_MAGIC); // magik
-        writer.write(VOXEL_MESH_VERSION); // version
+        self.encode_with(CompressionMode::None)
+    }
 
-        writer.write_u32(self.vertices.len() as _);
+    pub fn encode_with(&self, compression: CompressionMode) -> Vec<u8> {
+        let mut payload = ByteWriter::new();
+        payload.write_u32(self.vertices.len() as _);
 
         for vertex in &self.vertices {
-            writer.write_f32(vertex.position.x);
-            writer.write_f32(vertex.position.y);
-            writer.write_f32(vertex.position.z);
-            writer.write_u32(vertex.rgba);
+            payload.write_f32(vertex.position.x);
+            payload.write_f32(vertex.position.y);
+            payload.write_f32(vertex.position.z);
+            payload.write_u32(vertex.rgba);
         }
 
-
-        writer.write_u32(self.indices.len() as _);
+        payload.write_u32(self.indices.len() as _);
         for index in &self.indices {
-            writer.write_u32(*index);
+            payload.write_u32(*index);
         }
 
-        writer.finish()
+        let payload = payload.finish();
+        let payload_len = payload.len();
+
+        let body = match compression {
+            CompressionMode::None => payload,
+            CompressionMode::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&payload).expect("writing to an in-memory encoder cannot fail");
+                encoder.finish().expect("flushing an in-memory encoder cannot fail")
+            }
+        };
+
+        let mut writer = ByteWriter::new();
+        writer.write(VOXEL_MESH_MAGIC); // magik
+        writer.write(VOXEL_MESH_VERSION); // version
+        writer.write([compression as u8]);
+        writer.write_u32(payload_len as _); // uncompressed length
+
+        let mut out = writer.finish();
+        out.extend_from_slice(&body);
+
+        let mut hasher = Hasher::new();
+        hasher.update(&out[VOXEL_MESH_HEADER_LEN..]);
+
+        let mut crc_writer = ByteWriter::new();
+        crc_writer.write_u32(hasher.finalize());
+        out.extend_from_slice(&crc_writer.finish());
+
+        out
     }
 
 
     pub fn decode(data: &[u8]) -> Result<VoxelMesh, VoxelMeshDecodeError> {
-        let decode = || {
-        let Some(mut reader) = ByteReader::new(data)
-        else {
-            return Some(Err(VoxelMeshDecodeError::InvalidByteWriter));
-        };
-
-        let magic = reader.read()?;
+        let mut cursor = Cursor::new(data).ok_or(VoxelMeshDecodeError::InvalidByteWriter)?;
 
+        let magic = cursor.array::<10>("magic")?;
         if magic != VOXEL_MESH_MAGIC {
-            return Some(Err(VoxelMeshDecodeError::InvalidMagicValue));
+            return Err(VoxelMeshDecodeError::InvalidMagicValue);
         }
 
-        let version = reader.read()?;
+        let version = cursor.array::<4>("version")?;
         if version != VOXEL_MESH_VERSION {
-            return Some(Err(VoxelMeshDecodeError::InvalidVersion {
+            return Err(VoxelMeshDecodeError::InvalidVersion {
                 lib_version: VOXEL_MESH_VERSION,
                 file_version: version,
-            }));
+            });
         }
 
+        let compression = cursor.array::<1>("compression mode")?[0];
+        let uncompressed_len = cursor.u32("uncompressed length")? as usize;
 
-        let vertices_len = reader.read_u32()?;
-        let mut vertices = Vec::with_capacity(vertices_len as _);
+        let crc_start = data.len().checked_sub(4)
+            .ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: cursor.offset, reading: "crc32" })?;
+        let body = data.get(cursor.offset..crc_start)
+            .ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: cursor.offset, reading: "compressed body" })?;
 
+        let mut hasher = Hasher::new();
+        hasher.update(&data[VOXEL_MESH_HEADER_LEN..crc_start]);
+        let expected = hasher.finalize();
+
+        let mut crc_cursor = Cursor::new(&data[crc_start..])
+            .ok_or(VoxelMeshDecodeError::UnexpectedEof { offset: crc_start, reading: "crc32" })?;
+        let found = crc_cursor.u32("crc32")?;
+
+        if found != expected {
+            return Err(VoxelMeshDecodeError::ChecksumMismatch { expected, found });
+        }
+
+        let payload = match compression {
+            0 => body.to_vec(),
+            1 => {
+                let max_plausible = body.len().saturating_mul(MAX_ZLIB_EXPANSION_RATIO);
+                if uncompressed_len > max_plausible {
+                    return Err(VoxelMeshDecodeError::CorruptLength {
+                        field: "uncompressed length",
+                        declared: uncompressed_len as u32,
+                        remaining: body.len(),
+                    });
+                }
+
+                let mut out = Vec::with_capacity(uncompressed_len);
+                ZlibDecoder::new(body).read_to_end(&mut out)
+                    .map_err(|_| VoxelMeshDecodeError::UnexpectedEof { offset: cursor.offset, reading: "compressed body" })?;
+                out
+            }
+            other => return Err(VoxelMeshDecodeError::UnsupportedCompression(other)),
+        };
+
+        let mut cursor = Cursor::new(&payload).ok_or(VoxelMeshDecodeError::InvalidByteWriter)?;
+
+        let vertices_len = cursor.u32("vertex count")?;
+        let vertices_remaining = payload.len().saturating_sub(cursor.offset);
+        if (vertices_len as usize).checked_mul(VERTEX_BYTE_LEN).map_or(true, |len| len > vertices_remaining) {
+            return Err(VoxelMeshDecodeError::CorruptLength {
+                field: "vertex count",
+                declared: vertices_len,
+                remaining: vertices_remaining,
+            });
+        }
+
+        let mut vertices = Vec::with_capacity(vertices_len as _);
         for _ in 0..vertices_len {
-            let x = reader.read_f32()?;
-            let y = reader.read_f32()?;
-            let z = reader.read_f32()?;
-            let pos = Vec3::new(x, y, z);
+            let x = cursor.f32("vertex.position.x")?;
+            let y = cursor.f32("vertex.position.y")?;
+            let z = cursor.f32("vertex.position.z")?;
+            let rgba = cursor.u32("vertex.rgba")?;
 
-            let rgba = reader.read_u32()?;
+            vertices.push(Vertex::new(Vec3::new(x, y, z), rgba));
+        }
 
-            vertices.push(Vertex::new(pos, rgba));
+        let indices_len = cursor.u32("index count")?;
+        let indices_remaining = payload.len().saturating_sub(cursor.offset);
+        if (indices_len as usize).checked_mul(INDEX_BYTE_LEN).map_or(true, |len| len > indices_remaining) {
+            return Err(VoxelMeshDecodeError::CorruptLength {
+                field: "index count",
+                declared: indices_len,
+                remaining: indices_remaining,
+            });
         }
 
-        let indices_len = reader.read_u32()?;
-        let mut indices = Vec::with_capacity(vertices_len as _);
+        let mut indices = Vec::with_capacity(indices_len as _);
         for _ in 0..indices_len {
-            indices.push(reader.read_u32()?);
+            let index = cursor.u32("index")?;
+            if index as usize >= vertices.len() {
+                return Err(VoxelMeshDecodeError::IndexOutOfBounds {
+                    index,
+                    vertex_count: vertices.len() as u32,
+                });
+            }
+
+            indices.push(index);
         }
 
-        Some(Ok(Self { vertices, indices }))
-        };
+        Ok(Self { vertices, indices })
+    }
+
+    /// builds a [`bvh::Bvh`] over this mesh's triangles for ray-picking and collision queries
+    pub fn build_bvh(&self) -> bvh::Bvh {
+        bvh::Bvh::build(&self.vertices, &self.indices)
+    }
+
+    /// deduplicates vertices that share a quantized position and `rgba`, rewriting
+    /// `indices` to point at the shared vertex and truncating `vertices` to the
+    /// unique set. `tolerance` is the grid size positions are snapped to before
+    /// comparison; a smaller tolerance requires closer-to-exact matches to weld.
+    ///
+    /// returns the vertex count before and after welding.
+    pub fn weld(&mut self, tolerance: f32) -> (usize, usize) {
+        assert!(tolerance > 0.0, "weld tolerance must be positive, got {tolerance}");
+
+        let before = self.vertices.len();
+        let quantize = |v: f32| (v / tolerance).round() as i64;
+
+        let mut remap = HashMap::with_capacity(self.vertices.len());
+        let mut unique = Vec::with_capacity(self.vertices.len());
+        let mut new_index = vec![0u32; self.vertices.len()];
+
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let key = (
+                quantize(vertex.position.x),
+                quantize(vertex.position.y),
+                quantize(vertex.position.z),
+                vertex.rgba,
+            );
+
+            let index = *remap.entry(key).or_insert_with(|| {
+                unique.push(*vertex);
+                (unique.len() - 1) as u32
+            });
+
+            new_index[i] = index;
+        }
+
+        for index in &mut self.indices {
+            *index = new_index[*index as usize];
+        }
 
+        self.vertices = unique;
 
-        decode().unwrap_or(Err(VoxelMeshDecodeError::EOI))
+        (before, self.vertices.len())
     }
 }
 
@@ -318,3 +529,174 @@ pub fn greedy_mesh(rgba_data: &[u32], dims: USizeVec3, vertices: &mut Vec<Vertex
     true
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_mesh() -> VoxelMesh {
+        VoxelMesh {
+            vertices: vec![
+                Vertex::new(Vec3::new(0.0, 0.0, 0.0), 0xff0000ff),
+                Vertex::new(Vec3::new(1.0, 0.0, 0.0), 0xff0000ff),
+                Vertex::new(Vec3::new(1.0, 1.0, 0.0), 0xff0000ff),
+                Vertex::new(Vec3::new(0.0, 1.0, 0.0), 0xff0000ff),
+            ],
+            indices: vec![0, 1, 2, 2, 3, 0],
+        }
+    }
+
+    #[test]
+    fn zlib_round_trip_matches_original() {
+        let mesh = sample_mesh();
+        let encoded = mesh.encode_with(CompressionMode::Zlib);
+        let decoded = VoxelMesh::decode(&encoded).unwrap();
+        assert_eq!(mesh, decoded);
+    }
+
+    #[test]
+    fn decode_detects_checksum_mismatch() {
+        let mesh = sample_mesh();
+        let mut encoded = mesh.encode();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+
+        match VoxelMesh::decode(&encoded) {
+            Err(VoxelMeshDecodeError::ChecksumMismatch { .. }) => {}
+            other => panic!("expected ChecksumMismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn weld_merges_duplicate_vertices_from_adjacent_quads() {
+        // two quads sharing the x=1 edge, each pushed with its own fresh
+        // vertices the way `draw_quad` does, so vertex 1/4 and 2/7 duplicate
+        let vertices = vec![
+            Vertex::new(Vec3::new(0.0, 0.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, 0.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, 1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(0.0, 1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, 0.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(2.0, 0.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(2.0, 1.0, 0.0), 0xffffffff),
+            Vertex::new(Vec3::new(1.0, 1.0, 0.0), 0xffffffff),
+        ];
+        let indices = vec![0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
+
+        let mut mesh = VoxelMesh { vertices, indices };
+        let (before, after) = mesh.weld(1e-4);
+
+        assert_eq!(before, 8);
+        assert_eq!(after, 6);
+        assert_eq!(mesh.indices.len(), 12);
+        assert_eq!(mesh.indices[1], mesh.indices[11]); // shared vertex 1 == 4
+        assert_eq!(mesh.indices[2], mesh.indices[10]); // shared vertex 2 == 7
+
+        for &index in &mesh.indices {
+            assert!((index as usize) < mesh.vertices.len());
+        }
+    }
+
+    /// wraps a raw, uncompressed inner payload in a valid magic/version/compression/crc
+    /// envelope, mirroring `encode_with` but letting tests inject a malformed payload
+    fn wrap_payload(payload: &[u8]) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.write(VOXEL_MESH_MAGIC);
+        writer.write(VOXEL_MESH_VERSION);
+        writer.write([CompressionMode::None as u8]);
+        writer.write_u32(payload.len() as _);
+
+        let mut out = writer.finish();
+        out.extend_from_slice(payload);
+
+        let mut hasher = Hasher::new();
+        hasher.update(&out[VOXEL_MESH_HEADER_LEN..]);
+
+        let mut crc_writer = ByteWriter::new();
+        crc_writer.write_u32(hasher.finalize());
+        out.extend_from_slice(&crc_writer.finish());
+
+        out
+    }
+
+    #[test]
+    fn decode_reports_unexpected_eof_with_offset_and_field_name() {
+        let mesh = sample_mesh();
+        let mut encoded = mesh.encode();
+        encoded.truncate(VOXEL_MESH_HEADER_LEN); // cut right before the compression marker
+
+        match VoxelMesh::decode(&encoded) {
+            Err(VoxelMeshDecodeError::UnexpectedEof { offset, reading: "compression mode" }) => {
+                assert_eq!(offset, VOXEL_MESH_HEADER_LEN);
+            }
+            other => panic!("expected UnexpectedEof for \"compression mode\", got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_reports_corrupt_length_for_forged_vertex_count() {
+        let mut payload = ByteWriter::new();
+        payload.write_u32(u32::MAX); // forged vertex count, no vertex data follows
+        let payload = payload.finish();
+
+        let data = wrap_payload(&payload);
+
+        match VoxelMesh::decode(&data) {
+            Err(VoxelMeshDecodeError::CorruptLength { field: "vertex count", declared, remaining }) => {
+                assert_eq!(declared, u32::MAX);
+                assert_eq!(remaining, 0);
+            }
+            other => panic!("expected CorruptLength for \"vertex count\", got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_reports_corrupt_length_for_vertex_array_truncated_mid_vertex() {
+        // declares 2 vertices but only provides one full vertex's worth of bytes;
+        // the vertices_len/remaining-budget check added by chunk0-4 catches this
+        // before a single field read is attempted, rather than failing later with
+        // an UnexpectedEof inside the loop
+        let mut payload = ByteWriter::new();
+        payload.write_u32(2);
+        payload.write_f32(1.0);
+        payload.write_f32(2.0);
+        payload.write_f32(3.0);
+        payload.write_u32(0xff0000ff);
+        let payload = payload.finish();
+
+        let data = wrap_payload(&payload);
+
+        match VoxelMesh::decode(&data) {
+            Err(VoxelMeshDecodeError::CorruptLength { field: "vertex count", declared: 2, remaining }) => {
+                assert_eq!(remaining, VERTEX_BYTE_LEN);
+            }
+            other => panic!("expected CorruptLength for \"vertex count\", got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_detects_checksum_mismatch_mid_payload() {
+        let mesh = sample_mesh();
+        let mut encoded = mesh.encode();
+
+        let body_start = VOXEL_MESH_HEADER_LEN + 5; // compression byte + uncompressed length
+        let flip_at = body_start + (encoded.len() - body_start) / 2;
+        encoded[flip_at] ^= 0xff;
+
+        match VoxelMesh::decode(&encoded) {
+            Err(VoxelMeshDecodeError::ChecksumMismatch { .. }) => {}
+            other => panic!("expected ChecksumMismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn cursor_names_the_field_it_ran_out_reading() {
+        let mut cursor = Cursor::new(&[0u8]).expect("byte reader should construct from a non-empty slice");
+
+        match cursor.f32("vertex.position.x") {
+            Err(VoxelMeshDecodeError::UnexpectedEof { offset: 0, reading: "vertex.position.x" }) => {}
+            other => panic!("expected UnexpectedEof for \"vertex.position.x\", got {other:?}"),
+        }
+    }
+}
+